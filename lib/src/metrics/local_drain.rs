@@ -3,7 +3,10 @@ use std::time::Instant;
 use time::OffsetDateTime;
 use std::convert::TryInto;
 use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::io::Cursor;
 use hdrhistogram::Histogram;
+use hdrhistogram::serialization::{Serializer, V2Serializer, Deserializer};
 use sozu_command::proxy::{FilteredData,MetricsData,Percentiles,AppMetricsData,QueryMetricsType,QueryAnswerMetrics};
 
 use super::{MetricData,Subscriber};
@@ -53,6 +56,419 @@ impl AggregatedMetric {
   }
 }
 
+// cumulative upper bounds (in milliseconds) used when rendering time metrics
+// as Prometheus histograms, as powers of two up to ~16s
+const PROMETHEUS_TIME_BUCKETS: &[u64] = &[1, 2, 4, 8, 16, 32, 64, 128, 256, 512,
+                                          1024, 2048, 4096, 8192, 16384];
+
+// escape a label value following the Prometheus text exposition rules
+fn escape_label_value(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"'  => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            c    => res.push(c),
+        }
+    }
+    res
+}
+
+// join a set of dimensions into a `{k="v",...}` label block, or the empty
+// string when there is no dimension to emit
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let inner = labels.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{}}}", inner)
+}
+
+// render a single histogram's `_bucket`/`_sum`/`_count` sample lines,
+// accumulating cumulative counts up to each configured upper bound. The caller
+// is responsible for emitting the `# HELP`/`# TYPE` header once per family.
+// `scale` is the divisor that converts a recorded value (and the bucket bounds,
+// which share its unit) into the series' base unit — 1000.0 for a histogram
+// recorded in milliseconds but exported in `seconds`, 1.0 when they already
+// match.
+fn render_prometheus_histogram(out: &mut String, name: &str, labels: &[(&str, &str)], hist: &Histogram<u32>, scale: f64) {
+    for bound in PROMETHEUS_TIME_BUCKETS {
+        let mut bucket_labels = labels.to_vec();
+        let le = (*bound as f64 / scale).to_string();
+        bucket_labels.push(("le", &le));
+        out.push_str(&format!("{}_bucket{} {}\n", name, format_labels(&bucket_labels),
+                              hist.count_between(0, *bound)));
+    }
+    let mut inf_labels = labels.to_vec();
+    inf_labels.push(("le", "+Inf"));
+    out.push_str(&format!("{}_bucket{} {}\n", name, format_labels(&inf_labels), hist.len()));
+    let sum = hist.mean() * hist.len() as f64 / scale;
+    out.push_str(&format!("{}_sum{} {}\n", name, format_labels(labels), sum));
+    out.push_str(&format!("{}_count{} {}\n", name, format_labels(labels), hist.len()));
+}
+
+// number of significant figures kept by every time histogram; must stay
+// constant so histograms serialized in different windows can be merged
+const TIME_METRIC_SIGFIG: u8 = 3;
+
+// serialize a histogram into hdrhistogram's compact V2 byte representation for
+// storage in sled, returning None (and logging) if serialization fails
+fn serialize_histogram(hist: &Histogram<u32>) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    match V2Serializer::new().serialize(hist, &mut buf) {
+        Ok(_) => Some(buf),
+        Err(e) => {
+            error!("could not serialize time histogram: {:?}", e);
+            None
+        },
+    }
+}
+
+// deserialize a histogram from a stored V2 buffer, returning None (and logging)
+// rather than panicking when a buffer is corrupt
+fn deserialize_histogram(buf: &[u8]) -> Option<Histogram<u32>> {
+    match Deserializer::new().deserialize(&mut Cursor::new(buf)) {
+        Ok(h) => Some(h),
+        Err(e) => {
+            error!("could not deserialize time histogram: {:?}", e);
+            None
+        },
+    }
+}
+
+// a minimal MSB-first bit writer used by the Gorilla block encoder
+struct BitWriter {
+    bytes: Vec<u8>,
+    // number of bits already filled in the last byte (0..8)
+    bit:   u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), bit: 0 }
+    }
+
+    fn write_bit(&mut self, set: bool) {
+        if self.bit == 0 {
+            self.bytes.push(0);
+        }
+        if set {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 1 << (7 - self.bit);
+        }
+        self.bit = (self.bit + 1) % 8;
+    }
+
+    // write the `count` least-significant bits of `value`, most significant first
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    // zig-zag map a signed value and emit it as a LEB128-style varint, bit packed
+    fn write_varint_signed(&mut self, value: i64) {
+        let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+        loop {
+            let mut byte = (zigzag & 0x7f) as u64;
+            zigzag >>= 7;
+            if zigzag != 0 {
+                byte |= 0x80;
+            }
+            self.write_bits(byte, 8);
+            if zigzag == 0 {
+                break;
+            }
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// the matching MSB-first bit reader
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.pos / 8;
+        if byte >= self.bytes.len() {
+            return None;
+        }
+        let offset = self.pos % 8;
+        self.pos += 1;
+        Some((self.bytes[byte] >> (7 - offset)) & 1 == 1)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    fn read_varint_signed(&mut self) -> Option<i64> {
+        let mut result = 0u64;
+        let mut shift = 0u32;
+        loop {
+            let byte = self.read_bits(8)?;
+            result |= (byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        Some(((result >> 1) as i64) ^ -((result & 1) as i64))
+    }
+}
+
+// pack a sealed run of `(timestamp, value)` integer points into a single
+// compressed blob using the Gorilla scheme: the first timestamp is stored in
+// full, every following one as a delta-of-delta (a single `0` bit when the
+// cadence is steady, otherwise a `1` bit plus a zig-zag varint), and values as
+// zig-zag varint deltas from the previous sample. The archived float column
+// (time-metric window means) uses the companion `compress_float_block`, which
+// XORs successive IEEE-754 bit patterns instead of subtracting them.
+fn compress_int_block(points: &[(i64, i64)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(points.len() as u32).to_le_bytes());
+    if points.is_empty() {
+        return buf;
+    }
+
+    buf.extend_from_slice(&points[0].0.to_le_bytes());
+    buf.extend_from_slice(&points[0].1.to_le_bytes());
+
+    let mut writer = BitWriter::new();
+    let mut prev_ts = points[0].0;
+    let mut prev_delta = 0i64;
+    let mut prev_value = points[0].1;
+    for (ts, value) in &points[1..] {
+        let delta = ts - prev_ts;
+        let dod = delta - prev_delta;
+        if dod == 0 {
+            writer.write_bit(false);
+        } else {
+            writer.write_bit(true);
+            writer.write_varint_signed(dod);
+        }
+        writer.write_varint_signed(value - prev_value);
+        prev_ts = *ts;
+        prev_delta = delta;
+        prev_value = *value;
+    }
+
+    buf.extend_from_slice(&writer.into_bytes());
+    buf
+}
+
+// inverse of `compress_int_block`. A blob shorter than its header yields an
+// empty vec, and a bit stream that ends early yields the points decoded before
+// it ran out; the bit stream itself is not checked for corruption
+fn decompress_int_block(buf: &[u8]) -> Vec<(i64, i64)> {
+    if buf.len() < 4 {
+        return Vec::new();
+    }
+    let count = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+    if count == 0 {
+        return Vec::new();
+    }
+    if buf.len() < 20 {
+        return Vec::new();
+    }
+
+    let first_ts = i64::from_le_bytes(buf[4..12].try_into().unwrap());
+    let first_value = i64::from_le_bytes(buf[12..20].try_into().unwrap());
+
+    let mut points = Vec::with_capacity(count);
+    points.push((first_ts, first_value));
+
+    let mut reader = BitReader::new(&buf[20..]);
+    let mut prev_ts = first_ts;
+    let mut prev_delta = 0i64;
+    let mut prev_value = first_value;
+    for _ in 1..count {
+        let dod = match reader.read_bit() {
+            Some(true) => match reader.read_varint_signed() {
+                Some(d) => d,
+                None => break,
+            },
+            Some(false) => 0,
+            None => break,
+        };
+        let value_delta = match reader.read_varint_signed() {
+            Some(d) => d,
+            None => break,
+        };
+        let delta = prev_delta + dod;
+        let ts = prev_ts + delta;
+        let value = prev_value + value_delta;
+        points.push((ts, value));
+        prev_ts = ts;
+        prev_delta = delta;
+        prev_value = value;
+    }
+
+    points
+}
+
+// pack a sealed run of `(timestamp, value)` float points. Timestamps use the
+// same delta-of-delta scheme as `compress_int_block`; each value is stored as
+// the XOR of its IEEE-754 bits with the previous value's — a single `0` bit
+// when the bits are identical, otherwise a `1` bit followed by the number of
+// leading zero bytes, the number of meaningful bytes, and those bytes. This is
+// the Gorilla float column the integer encoder's doc comment refers to.
+fn compress_float_block(points: &[(i64, f64)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(points.len() as u32).to_le_bytes());
+    if points.is_empty() {
+        return buf;
+    }
+
+    buf.extend_from_slice(&points[0].0.to_le_bytes());
+    buf.extend_from_slice(&points[0].1.to_bits().to_le_bytes());
+
+    let mut writer = BitWriter::new();
+    let mut prev_ts = points[0].0;
+    let mut prev_delta = 0i64;
+    let mut prev_bits = points[0].1.to_bits();
+    for (ts, value) in &points[1..] {
+        let delta = ts - prev_ts;
+        let dod = delta - prev_delta;
+        if dod == 0 {
+            writer.write_bit(false);
+        } else {
+            writer.write_bit(true);
+            writer.write_varint_signed(dod);
+        }
+
+        let bits = value.to_bits();
+        let xor = bits ^ prev_bits;
+        if xor == 0 {
+            writer.write_bit(false);
+        } else {
+            writer.write_bit(true);
+            // store the XOR as a byte run: leading zero bytes, meaningful byte
+            // count, then the meaningful bytes, most significant first
+            let leading = (xor.leading_zeros() / 8) as u64;
+            let trailing = (xor.trailing_zeros() / 8) as u64;
+            let meaningful = 8 - leading - trailing;
+            writer.write_bits(leading, 3);
+            writer.write_bits(meaningful - 1, 3);
+            let shifted = xor >> (trailing * 8);
+            writer.write_bits(shifted, (meaningful * 8) as u32);
+        }
+
+        prev_ts = *ts;
+        prev_delta = delta;
+        prev_bits = bits;
+    }
+
+    buf.extend_from_slice(&writer.into_bytes());
+    buf
+}
+
+// inverse of `compress_float_block`. A blob shorter than its header yields an
+// empty vec, and a bit stream that ends early yields the points decoded before
+// it ran out
+fn decompress_float_block(buf: &[u8]) -> Vec<(i64, f64)> {
+    if buf.len() < 4 {
+        return Vec::new();
+    }
+    let count = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+    if count == 0 {
+        return Vec::new();
+    }
+    if buf.len() < 20 {
+        return Vec::new();
+    }
+
+    let first_ts = i64::from_le_bytes(buf[4..12].try_into().unwrap());
+    let first_bits = u64::from_le_bytes(buf[12..20].try_into().unwrap());
+
+    let mut points = Vec::with_capacity(count);
+    points.push((first_ts, f64::from_bits(first_bits)));
+
+    let mut reader = BitReader::new(&buf[20..]);
+    let mut prev_ts = first_ts;
+    let mut prev_delta = 0i64;
+    let mut prev_bits = first_bits;
+    for _ in 1..count {
+        let dod = match reader.read_bit() {
+            Some(true) => match reader.read_varint_signed() {
+                Some(d) => d,
+                None => break,
+            },
+            Some(false) => 0,
+            None => break,
+        };
+
+        let bits = match reader.read_bit() {
+            Some(false) => prev_bits,
+            Some(true) => {
+                let leading = match reader.read_bits(3) {
+                    Some(v) => v,
+                    None => break,
+                };
+                let meaningful = match reader.read_bits(3) {
+                    Some(v) => v + 1,
+                    None => break,
+                };
+                let trailing = 8 - leading - meaningful;
+                let shifted = match reader.read_bits((meaningful * 8) as u32) {
+                    Some(v) => v,
+                    None => break,
+                };
+                prev_bits ^ (shifted << (trailing * 8))
+            },
+            None => break,
+        };
+
+        let delta = prev_delta + dod;
+        let ts = prev_ts + delta;
+        points.push((ts, f64::from_bits(bits)));
+        prev_ts = ts;
+        prev_delta = delta;
+        prev_bits = bits;
+    }
+
+    points
+}
+
+// pull the trailing `\t{timestamp}` segment off a per-point key
+fn parse_point_timestamp(key: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(key).ok()?;
+    s.rsplit('\t').next()?.parse().ok()
+}
+
+// percentile used when reducing a time series over a query window
+const RANGE_QUERY_PERCENTILE: f64 = 50.0;
+
+// nearest-rank percentile of an unsorted slice of values
+fn percentile_of(values: &[f64], percentile: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = ((percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+    let index = rank.saturating_sub(1).min(sorted.len() - 1);
+    sorted[index]
+}
+
 pub fn histogram_to_percentiles(hist: &Histogram<u32>) -> Percentiles {
   Percentiles {
     samples:  hist.len(),
@@ -88,8 +504,18 @@ pub struct BackendMetrics {
   pub data:   BTreeMap<String, AggregatedMetric>,
 }
 
-#[derive(Clone,Debug,PartialEq)]
-enum MetricKind {
+// a single metric emitted with its dimensions kept explicit instead of flattened
+// into a tab-joined key, so consumers (StatsD-with-tags, Prometheus labels,
+// InfluxDB line protocol) can fan it out without re-parsing the key
+#[derive(Clone,Debug)]
+pub struct TaggedMetric {
+  pub name:  String,
+  pub tags:  Vec<(String, String)>,
+  pub value: FilteredData,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum MetricKind {
   Gauge,
   Count,
   Time,
@@ -101,6 +527,116 @@ enum MetricMeta {
     ClusterBackend,
 }
 
+// unit descriptor attached to every registered metric so downstream consumers
+// can interpret and scale a raw value instead of guessing from the name
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum MetricUnit {
+    Bytes,
+    Seconds,
+    Milliseconds,
+    Count,
+    Percent,
+}
+
+impl MetricUnit {
+    // suffix appended to a Prometheus series name, following the base-unit
+    // naming convention (seconds/bytes), or None when the unit is dimensionless
+    fn prometheus_suffix(&self) -> Option<&'static str> {
+        match self {
+            MetricUnit::Bytes        => Some("bytes"),
+            MetricUnit::Seconds      => Some("seconds"),
+            MetricUnit::Milliseconds => Some("seconds"),
+            MetricUnit::Count        => None,
+            MetricUnit::Percent      => Some("ratio"),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricUnit::Bytes        => "bytes",
+            MetricUnit::Seconds      => "seconds",
+            MetricUnit::Milliseconds => "milliseconds",
+            MetricUnit::Count        => "count",
+            MetricUnit::Percent      => "percent",
+        }
+    }
+}
+
+// guess a reasonable unit from the metric name when the caller does not supply
+// one: time metrics are milliseconds, anything byte-related is bytes, names
+// mentioning a percent or ratio are percentages, the rest are plain counts
+fn infer_unit(name: &str, kind: &MetricKind) -> MetricUnit {
+    if *kind == MetricKind::Time {
+        return MetricUnit::Milliseconds;
+    }
+    let lower = name.to_lowercase();
+    if lower.contains("byte") || lower.contains("_mem") {
+        MetricUnit::Bytes
+    } else if lower.contains("percent") || lower.contains("ratio") {
+        MetricUnit::Percent
+    } else {
+        MetricUnit::Count
+    }
+}
+
+// short human-readable description stored alongside each metric and surfaced
+// through `QueryAnswerMetrics::List` and the `# HELP` line of the exporter
+fn metric_description(key_prefix: &str, kind: &MetricKind, unit: MetricUnit) -> String {
+    let name = key_prefix.split('\t').next().unwrap_or(key_prefix);
+    let role = match kind {
+        MetricKind::Gauge => "gauge",
+        MetricKind::Count => "counter",
+        MetricKind::Time  => "latency",
+    };
+    format!("{} {} in {}", name, role, unit.as_str())
+}
+
+// how a closing window is reduced into a single coarser point; Time metrics
+// merge their per-window histograms instead and are not folded here
+#[derive(Clone, Copy, Debug)]
+enum Downsample {
+    Sum,
+    Last,
+}
+
+// one rung of the rollup ladder: points `resolution` seconds apart. A tier's
+// points are folded into the next, coarser tier when the clock reaches that
+// tier's resolution boundary. `retention` is only read on the coarsest tier,
+// whose points are pruned once older than it; finer tiers do not use it.
+#[derive(Clone, Debug)]
+pub struct RetentionTier {
+  pub resolution: i64,
+  pub retention:  i64,
+}
+
+// an ordered, finest-first list of retention tiers. Operators can trade disk
+// for history by lengthening the coarsest tier's retention or adding coarser
+// rungs.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+  pub tiers: Vec<RetentionTier>,
+}
+
+impl RetentionPolicy {
+  pub fn new(tiers: Vec<RetentionTier>) -> RetentionPolicy {
+    RetentionPolicy { tiers }
+  }
+}
+
+impl Default for RetentionPolicy {
+  // the historical cadence: raw seconds folded to the minute, minutes kept an
+  // hour then folded to the hour, hours kept a day
+  fn default() -> RetentionPolicy {
+    RetentionPolicy {
+      tiers: vec![
+        RetentionTier { resolution: 1,    retention: 60 },
+        RetentionTier { resolution: 60,   retention: 3600 },
+        RetentionTier { resolution: 3600, retention: 3600 * 24 },
+      ],
+    }
+  }
+}
+
 #[derive(Debug)]
 pub struct LocalDrain {
   pub prefix:          String,
@@ -108,9 +644,20 @@ pub struct LocalDrain {
   pub db:              sled::Db,
   pub cluster_tree:    sled::Tree,
   pub backend_tree:    sled::Tree,
+  // sealed coarsest-tier runs (and archived time-metric means) are packed into
+  // Gorilla-compressed blobs here, keyed by the run's closing timestamp,
+  // instead of one key per point
+  pub cluster_blocks:  sled::Tree,
+  pub backend_blocks:  sled::Tree,
   pub data:            BTreeMap<String, AggregatedMetric>,
-  metrics:             BTreeMap<String, (MetricMeta, MetricKind)>,
+  metrics:             BTreeMap<String, (MetricMeta, MetricKind, MetricUnit, String, Vec<(String, String)>)>,
   use_tagged_metrics:  bool,
+  // when set, the coarsest tier's closing window is compressed into a single
+  // block value rather than folded into one point; off by default to preserve
+  // the old layout
+  use_compression:     bool,
+  // the rollup/prune ladder driving aggregation; see `RetentionPolicy`
+  retention:           RetentionPolicy,
   origin:              String,
 }
 
@@ -123,6 +670,8 @@ impl LocalDrain {
         .unwrap();
     let cluster_tree = db.open_tree("cluster").unwrap();
     let backend_tree = db.open_tree("backend").unwrap();
+    let cluster_blocks = db.open_tree("cluster_blocks").unwrap();
+    let backend_blocks = db.open_tree("backend_blocks").unwrap();
 
     LocalDrain {
       prefix,
@@ -130,13 +679,41 @@ impl LocalDrain {
       db,
       cluster_tree,
       backend_tree,
+      cluster_blocks,
+      backend_blocks,
       metrics:     BTreeMap::new(),
       data:        BTreeMap::new(),
       use_tagged_metrics: false,
+      use_compression: false,
+      retention:   RetentionPolicy::default(),
       origin:      String::from("x"),
     }
   }
 
+  // override the default rollup/retention ladder, e.g. to keep more history
+  pub fn with_retention(mut self, policy: RetentionPolicy) -> Self {
+    self.retention = policy;
+    self
+  }
+
+  // seal the coarsest retention tier into Gorilla-compressed blocks instead of
+  // keeping it as one key per point. Off by default to preserve the historical
+  // layout. The archived blocks are folded back in by `dump_cluster_data` and
+  // `query_range`; the other read paths only see the live keys.
+  pub fn with_compression(mut self, on: bool) -> Self {
+    self.use_compression = on;
+    self
+  }
+
+  // enable `dump_tagged_metrics` and label every emitted metric with the given
+  // origin (typically the worker or host name), so a tag-aware sink can tell
+  // apart the series coming from different sozu processes
+  pub fn with_tagged_metrics(mut self, origin: String) -> Self {
+    self.use_tagged_metrics = true;
+    self.origin = origin;
+    self
+  }
+
   pub fn dump_metrics_data(&mut self) -> MetricsData {
     MetricsData {
       proxy:    self.dump_process_data(),
@@ -154,11 +731,182 @@ impl LocalDrain {
     data
   }
 
+  // render the current metrics in the Prometheus text exposition format so an
+  // external Prometheus can scrape sozu directly over HTTP at `/metrics`
+  pub fn dump_prometheus_metrics(&self) -> Result<String, sled::Error> {
+      let mut out = String::new();
+      // the text exposition format rejects a repeated `# HELP`/`# TYPE` for one
+      // metric name, so every family's header is emitted exactly once even when
+      // it fans out into many cluster/backend series
+      let mut emitted: HashSet<String> = HashSet::new();
+
+      // process-wide metrics kept in memory
+      for (key, metric) in self.data.iter() {
+          let name = key.replace('.', "_");
+          match metric {
+              AggregatedMetric::Gauge(v) => {
+                  if emitted.insert(name.clone()) {
+                      out.push_str(&format!("# TYPE {} gauge\n", name));
+                  }
+                  out.push_str(&format!("{} {}\n", name, v));
+              },
+              AggregatedMetric::Count(v) => {
+                  if emitted.insert(name.clone()) {
+                      out.push_str(&format!("# TYPE {} counter\n", name));
+                  }
+                  out.push_str(&format!("{} {}\n", name, v));
+              },
+              AggregatedMetric::Time(hist) => {
+                  if emitted.insert(name.clone()) {
+                      out.push_str(&format!("# TYPE {} histogram\n", name));
+                  }
+                  // in-memory latencies are recorded in milliseconds, exported in seconds
+                  render_prometheus_histogram(&mut out, &name, &[], hist, 1000.0);
+              },
+          }
+      }
+
+      // cluster and backend metrics persisted in the trees: the tab-separated
+      // key segments become Prometheus labels
+      for (key, (meta, kind, unit, description, _tags)) in self.metrics.iter() {
+          let mut it = key.split('\t');
+          let name = match it.next() {
+              Some(n) => n.replace('.', "_"),
+              None => continue,
+          };
+          let cluster = it.next();
+          let backend = it.next();
+
+          let mut labels: Vec<(&str, &str)> = Vec::new();
+          if let Some(c) = cluster {
+              labels.push(("cluster", c));
+          }
+          if let Some(b) = backend {
+              labels.push(("backend", b));
+          }
+
+          // follow the Prometheus base-unit naming convention by appending the
+          // unit suffix when there is one
+          let name = match unit.prometheus_suffix() {
+              Some(suffix) if !name.ends_with(suffix) => format!("{}_{}", name, suffix),
+              _ => name,
+          };
+
+          let end = format!("{}\x7F", key);
+          let is_backend = *meta == MetricMeta::ClusterBackend;
+          match kind {
+              MetricKind::Gauge => {
+                  if let Some(v) = self.get_last_before(key, &end, is_backend)? {
+                      if emitted.insert(name.clone()) {
+                          out.push_str(&format!("# HELP {} {}\n", name, description));
+                          out.push_str(&format!("# TYPE {} gauge\n", name));
+                      }
+                      out.push_str(&format!("{}{} {}\n", name, format_labels(&labels),
+                                            usize::from_le_bytes((*v).try_into().unwrap())));
+                  }
+              },
+              MetricKind::Count => {
+                  if let Some(v) = self.get_last_before(key, &end, is_backend)? {
+                      if emitted.insert(name.clone()) {
+                          out.push_str(&format!("# HELP {} {} (per-window total, reset each aggregation window)\n", name, description));
+                          // these values are reduced per aggregation window and
+                          // reset rather than accumulate, so they are exposed as a
+                          // gauge — rate()/increase() over a counter would see the
+                          // resets as negative jumps and report garbage
+                          out.push_str(&format!("# TYPE {} gauge\n", name));
+                      }
+                      out.push_str(&format!("{}{} {}\n", name, format_labels(&labels),
+                                            i64::from_le_bytes((*v).try_into().unwrap())));
+                  }
+              },
+              MetricKind::Time => {
+                  // merge the per-window histograms and expose them as a native
+                  // Prometheus histogram with the cluster/backend labels
+                  if let Some(hist) = self.merged_time_histogram(key, is_backend)? {
+                      if emitted.insert(name.clone()) {
+                          out.push_str(&format!("# HELP {} {}\n", name, description));
+                          out.push_str(&format!("# TYPE {} histogram\n", name));
+                      }
+                      // latencies are recorded in milliseconds; convert to the
+                      // `seconds` base unit carried by the name suffix
+                      let scale = if *unit == MetricUnit::Milliseconds { 1000.0 } else { 1.0 };
+                      render_prometheus_histogram(&mut out, &name, &labels, &hist, scale);
+                  }
+              },
+          }
+      }
+
+      Ok(out)
+  }
+
+  // emit every metric as a `TaggedMetric` carrying explicit dimensions instead
+  // of a tab-joined key, so tag-aware consumers can fan metrics out by
+  // dimension. Only active when `use_tagged_metrics` is set; the `origin` of
+  // this drain is always added as a dimension.
+  pub fn dump_tagged_metrics(&self) -> Result<Vec<TaggedMetric>, sled::Error> {
+      if !self.use_tagged_metrics {
+          return Ok(Vec::new());
+      }
+
+      let mut out = Vec::new();
+      for (key, (meta, kind, _unit, _description, caller_tags)) in self.metrics.iter() {
+          let mut it = key.split('\t');
+          let name = match it.next() {
+              Some(n) => n.to_string(),
+              None => continue,
+          };
+
+          let mut tags: Vec<(String, String)> = vec![("origin".to_string(), self.origin.clone())];
+          if let Some(cluster) = it.next() {
+              tags.push(("cluster".to_string(), cluster.to_string()));
+          }
+          if let Some(backend) = it.next() {
+              tags.push(("backend".to_string(), backend.to_string()));
+          }
+          tags.extend(caller_tags.iter().cloned());
+
+          let is_backend = *meta == MetricMeta::ClusterBackend;
+          let value = match kind {
+              MetricKind::Time => {
+                  match self.merged_time_histogram(key, is_backend)? {
+                      Some(hist) => FilteredData::Percentiles(histogram_to_percentiles(&hist)),
+                      None => continue,
+                  }
+              },
+              _ => {
+                  let end = format!("{}\x7F", key);
+                  match self.get_last_before(key, &end, is_backend)? {
+                      Some(v) => match kind {
+                          MetricKind::Gauge => FilteredData::Gauge(usize::from_le_bytes((*v).try_into().unwrap())),
+                          MetricKind::Count => FilteredData::Count(i64::from_le_bytes((*v).try_into().unwrap())),
+                          MetricKind::Time => unreachable!(),
+                      },
+                      None => continue,
+                  }
+              },
+          };
+
+          out.push(TaggedMetric { name, tags, value });
+      }
+
+      Ok(out)
+  }
+
   pub fn query(&mut self, q: &QueryMetricsType) -> Result<QueryAnswerMetrics, String> {
       info!("GOT QUERY: {:?}", q);
       match q {
           QueryMetricsType::List => {
-              Ok(QueryAnswerMetrics::List(self.metrics.keys().cloned().collect()))
+              // the registry now carries `(kind, unit, description)` alongside each
+              // name; surface them as tab-separated `name\tkind\tunit\tdescription`
+              // entries so a consumer can interpret and scale a series without
+              // guessing. The leading `v1\t` line is a format marker: older
+              // consumers that expected bare metric names can detect the richer
+              // layout instead of silently mis-parsing the first field.
+              let mut list = vec![String::from("v1\tkind\tunit\tdescription")];
+              list.extend(self.metrics.iter().map(|(name, (_meta, kind, unit, description, _tags))| {
+                  format!("{}\t{:?}\t{}\t{}", name, kind, unit.as_str(), description)
+              }));
+              Ok(QueryAnswerMetrics::List(list))
           },
           QueryMetricsType::Cluster { metrics, clusters } => {
               self.query_cluster(metrics, clusters).map_err(|e| {
@@ -191,102 +939,18 @@ impl LocalDrain {
                   error!("unknown metric key {}", key);
                   continue
               }
-              let (meta, kind) = res.unwrap();
+              let (_meta, kind, _unit, _description, _tags) = res.unwrap();
 
               //FIXME: check here that the metric is a cluster level one
 
               if *kind == MetricKind::Time {
-                  let mut percentiles = Percentiles::default();
-
-                  let count_key = format!("{}\t{}.count ", prefix_key, cluster_id);
-                  let count_end = format!("{}\x7F", count_key);
-
-                  if let Some(v) = self.get_last_before(&count_key, &count_end, false)? {
-                      let value = usize::from_le_bytes((*v).try_into().unwrap());
-                      //info!("count -> {} ({:?})", value, *v);
-                      percentiles.samples = value as u64;
-                  }
-
-                  {
-                      let p50_key = format!("{}\t{}.p50 ", prefix_key, cluster_id);
-                      let p50_end = format!("{}\x7F", p50_key);
-
-                      if let Some(v) = self.get_last_before(&p50_key, &p50_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p50 -> {} ({:?})", value, *v);
-                          percentiles.p_50 = value as u64;
-                      }
+                  // percentiles now come from merging the per-window histograms
+                  // rather than reading the old per-pXX moving-estimator keys
+                  if let Some(hist) = self.merged_time_histogram(&key, false)? {
+                      apps.get_mut(cluster_id).unwrap()
+                          .insert(key.to_string(), FilteredData::Percentiles(histogram_to_percentiles(&hist)));
                   }
 
-                  {
-                      let p90_key = format!("{}\t{}.p90 ", prefix_key, cluster_id);
-                      let p90_end = format!("{}\x7F", p90_key);
-
-                      if let Some(v) = self.get_last_before(&p90_key, &p90_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p90 -> {} ({:?})", value, *v);
-                          percentiles.p_90 = value as u64;
-                      }
-                  }
-
-                  {
-                      let p99_key = format!("{}\t{}.p99 ", prefix_key, cluster_id);
-                      let p99_end = format!("{}\x7F", p99_key);
-
-                      if let Some(v) = self.get_last_before(&p99_key, &p99_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p99 -> {} ({:?})", value, *v);
-                          percentiles.p_99 = value as u64;
-                      }
-                  }
-
-                  {
-                      let p99_9_key = format!("{}\t{}.p99.9 ", prefix_key, cluster_id);
-                      let p99_9_end = format!("{}\x7F", p99_9_key);
-
-                      if let Some(v) = self.get_last_before(&p99_9_key, &p99_9_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p99.9 -> {} ({:?})", value, *v);
-                          percentiles.p_99_9 = value as u64;
-                      }
-                  }
-
-                  {
-                      let p99_99_key = format!("{}\t{}.p99.99 ", prefix_key, cluster_id);
-                      let p99_99_end = format!("{}\x7F", p99_99_key);
-
-                      if let Some(v) = self.get_last_before(&p99_99_key, &p99_99_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p99.99 -> {} ({:?})", value, *v);
-                          percentiles.p_99_99 = value as u64;
-                      }
-                  }
-
-                  {
-                      let p99_999_key = format!("{}\t{}.p99.999 ", prefix_key, cluster_id);
-                      let p99_999_end = format!("{}\x7F", p99_999_key);
-
-                      if let Some(v) = self.get_last_before(&p99_999_key, &p99_999_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p99.999 -> {} ({:?})", value, *v);
-                          percentiles.p_99_999 = value as u64;
-                      }
-                  }
-
-                  {
-                      let p100_key = format!("{}\t{}.p100 ", prefix_key, cluster_id);
-                      let p100_end = format!("{}\x7F", p100_key);
-
-                      if let Some(v) = self.get_last_before(&p100_key, &p100_end, false)? {
-                          let value = usize::from_le_bytes((*v).try_into().unwrap());
-                          //info!("p100 -> {} ({:?})", value, *v);
-                          percentiles.p_100 = value as u64;
-                      }
-                  }
-
-                  apps.get_mut(cluster_id).unwrap()
-                      .insert(key.to_string(), FilteredData::Percentiles(percentiles));
-
                   continue;
               }
 
@@ -327,7 +991,18 @@ impl LocalDrain {
                   error!("unknown metric key {}", key);
                   continue
               }
-              let (meta, kind) = res.unwrap();
+              let (_meta, kind, _unit, _description, _tags) = res.unwrap();
+
+              if *kind == MetricKind::Time {
+                  // merge every stored window into one histogram and derive the
+                  // percentiles from it
+                  if let Some(hist) = self.merged_time_histogram(&key, true)? {
+                      backend_data.get_mut(cluster_id).unwrap()
+                          .get_mut(backend_id).unwrap()
+                          .insert(key.to_string(), FilteredData::Percentiles(histogram_to_percentiles(&hist)));
+                  }
+                  continue;
+              }
 
               let end = format!("{}\x7F", key);
               if let Some(v) = self.get_last_before(&key, &end, true)? {
@@ -340,9 +1015,7 @@ impl LocalDrain {
                           backend_data.get_mut(cluster_id).unwrap()
                               .get_mut(backend_id).unwrap().insert(key.to_string(), FilteredData::Count(i64::from_le_bytes((*v).try_into().unwrap())));
                       },
-                      MetricKind::Time => {
-                          //unimplemented for now
-                      }
+                      MetricKind::Time => {}
                   }
               }
           }
@@ -352,6 +1025,103 @@ impl LocalDrain {
       Ok(QueryAnswerMetrics::Backend(backend_data))
   }
 
+  // pull a metric series back out over `[from, to]` for plotting. When the
+  // stored points (live keys plus any archived compressed blocks) exceed
+  // `max_points`, the range is split into `max_points` equal windows and each
+  // window is reduced by the metric's kind — sum for counters, mean for gauges,
+  // a percentile for time metrics — so a caller gets evenly spaced, pixel-sized
+  // samples without transferring every second. The kind is returned so the
+  // caller knows which reduction was applied.
+  pub fn query_range(&self, key: &str, cluster_id: Option<&str>, backend_id: Option<&str>,
+                     from: i64, to: i64, max_points: usize) -> (MetricKind, Vec<(i64, f64)>) {
+      let prefix = match (cluster_id, backend_id) {
+          (Some(c), Some(b)) => format!("{}\t{}\t{}", key, c, b),
+          (Some(c), None)    => format!("{}\t{}", key, c),
+          _                  => key.to_string(),
+      };
+      let is_backend = backend_id.is_some();
+      let kind = self.metrics.get(&prefix).map(|(_, k, _, _, _)| *k).unwrap_or(MetricKind::Count);
+
+      let tree = if is_backend {
+          &self.backend_tree
+      } else {
+          &self.cluster_tree
+      };
+
+      // gather the raw points in range, one `(timestamp, value)` per sample
+      let mut points: Vec<(i64, f64)> = Vec::new();
+      match kind {
+          MetricKind::Time => {
+              // time metrics keep a histogram per window; take a percentile of
+              // each as the series value
+              let start = format!("{}.hist \t{}", prefix, from);
+              let end = format!("{}.hist \t{}", prefix, to + 1);
+              for res in tree.range(start.as_bytes()..end.as_bytes()) {
+                  if let Ok((k, v)) = res {
+                      if let (Some(ts), Some(hist)) = (parse_point_timestamp(&k), deserialize_histogram(&v)) {
+                          points.push((ts, hist.value_at_percentile(RANGE_QUERY_PERCENTILE) as f64));
+                      }
+                  }
+              }
+              // fold in any archived window-mean blocks for the older tail; these
+              // are means rather than percentiles, traded for a compact archive
+              let mean_col = format!("{}.mean ", prefix);
+              if let Ok(archived) = self.float_blocks_in_range(&mean_col, is_backend, from, to) {
+                  points.extend(archived);
+              }
+              points.sort_by_key(|(ts, _)| *ts);
+          },
+          _ => {
+              let start = format!("{}\t{}", prefix, from);
+              let end = format!("{}\t{}", prefix, to + 1);
+              for res in tree.range(start.as_bytes()..end.as_bytes()) {
+                  if let Ok((k, v)) = res {
+                      if let (Some(ts), Ok(bytes)) = (parse_point_timestamp(&k), (*v).try_into()) {
+                          points.push((ts, i64::from_le_bytes(bytes) as f64));
+                      }
+                  }
+              }
+              // fold in any archived compressed blocks covering the range
+              if let Ok(archived) = self.blocks_in_range(&prefix, is_backend, from, to) {
+                  points.extend(archived.into_iter().map(|(ts, v)| (ts, v as f64)));
+              }
+              points.sort_by_key(|(ts, _)| *ts);
+          },
+      }
+
+      if max_points == 0 || points.len() <= max_points {
+          return (kind, points);
+      }
+
+      // bucket the range into `max_points` windows and reduce each
+      let span = (to - from).max(1);
+      let width = (span as f64 / max_points as f64).max(1.0);
+      let mut buckets: Vec<Vec<f64>> = vec![Vec::new(); max_points];
+      for (ts, value) in &points {
+          let mut index = (((ts - from) as f64) / width) as usize;
+          if index >= max_points {
+              index = max_points - 1;
+          }
+          buckets[index].push(*value);
+      }
+
+      let mut out = Vec::new();
+      for (i, bucket) in buckets.iter().enumerate() {
+          if bucket.is_empty() {
+              continue;
+          }
+          let ts = from + (i as f64 * width) as i64;
+          let reduced = match kind {
+              MetricKind::Count => bucket.iter().sum::<f64>(),
+              MetricKind::Gauge => bucket.iter().sum::<f64>() / bucket.len() as f64,
+              MetricKind::Time  => percentile_of(bucket, RANGE_QUERY_PERCENTILE),
+          };
+          out.push((ts, reduced));
+      }
+
+      (kind, out)
+  }
+
   fn get_last_before(&self, start: &str, end: &str, is_backend: bool) -> Result<Option<sled::IVec>, sled::Error> {
       let tree = if is_backend {
           &self.backend_tree
@@ -396,9 +1166,69 @@ impl LocalDrain {
   pub fn dump_cluster_data(&mut self) -> Result<BTreeMap<String,AppMetricsData>, sled::Error> {
       let mut apps = BTreeMap::new();
 
-      for (key, (meta, kind)) in self.metrics.iter() {
+      for (key, (meta, kind, _unit, _description, _tags)) in self.metrics.iter() {
           let end = format!("{}\x7F", key);
 
+          // time metrics are stored as serialized histogram windows rather than
+          // plain values; merge them and emit percentiles before the scalar path
+          if *kind == MetricKind::Time {
+              let is_backend = *meta == MetricMeta::ClusterBackend;
+              if let Some(hist) = self.merged_time_histogram(key, is_backend)? {
+                  let mut it = key.split('\t');
+                  let name = it.next().unwrap_or(key);
+                  let app_id = match it.next() {
+                      Some(a) => a,
+                      None => continue,
+                  };
+                  let percentiles = FilteredData::Percentiles(histogram_to_percentiles(&hist));
+
+                  let app_metrics_data = apps.entry(app_id.to_string()).or_insert_with(AppMetricsData::new);
+                  match meta {
+                      MetricMeta::Cluster => {
+                          app_metrics_data.data.insert(name.to_string(), percentiles);
+                      },
+                      MetricMeta::ClusterBackend => {
+                          if let Some(backend_id) = it.next() {
+                              app_metrics_data.backends.entry(backend_id.to_string())
+                                  .or_insert_with(BTreeMap::new)
+                                  .insert(name.to_string(), percentiles);
+                          }
+                      },
+                  }
+              }
+              continue;
+          }
+
+          // fold any archived compressed blocks back in before the live points;
+          // the per-second keys below overwrite a sealed point at the same name
+          let is_backend = *meta == MetricMeta::ClusterBackend;
+          for (_ts, value) in self.blocks_in_range(key, is_backend, i64::MIN, i64::MAX)? {
+              let mut it = key.split('\t');
+              let name = it.next().unwrap_or(key);
+              let app_id = match it.next() {
+                  Some(a) => a,
+                  None => continue,
+              };
+              let fd = match kind {
+                  MetricKind::Gauge => FilteredData::Gauge(value as usize),
+                  MetricKind::Count => FilteredData::Count(value),
+                  MetricKind::Time  => continue,
+              };
+              let app_metrics_data = apps.entry(app_id.to_string()).or_insert_with(AppMetricsData::new);
+              match meta {
+                  MetricMeta::Cluster => {
+                      app_metrics_data.data.insert(name.to_string(), fd);
+                  },
+                  MetricMeta::ClusterBackend => {
+                      if let Some(backend_id) = it.next() {
+                          app_metrics_data.backends.entry(backend_id.to_string())
+                              .or_insert_with(BTreeMap::new)
+                              .insert(name.to_string(), fd);
+                      }
+                  },
+              }
+          }
+
           match meta {
               MetricMeta::Cluster => {
                   for res in self.cluster_tree.range(key.as_bytes()..end.as_bytes()) {
@@ -482,31 +1312,41 @@ impl LocalDrain {
       Ok(apps)
   }
 
-  fn receive_cluster_metric(&mut self, key: &str, cluster_id: &str, backend_id: Option<&str>, metric: MetricData) {
+  fn receive_cluster_metric(&mut self, key: &str, cluster_id: &str, backend_id: Option<&str>, unit: Option<MetricUnit>, tags: &[(String, String)], metric: MetricData) {
       info!("metric: {} {} {:?} {:?}", key, cluster_id, backend_id, metric);
 
       if let MetricData::Time(t) = metric {
-         if let Err(e) = self.store_time_metric(key, cluster_id, None, t) {
+         if let Err(e) = self.store_time_metric(key, cluster_id, None, unit, tags, t) {
+             error!("metrics database error: {:?}", e);
+         }
+         // also persist a mergeable histogram so backend latency can be
+         // rolled up across windows on the read path
+         let cluster_prefix = format!("{}\t{}", key, cluster_id);
+         if let Err(e) = self.store_time_histogram(&cluster_prefix, false, OffsetDateTime::now_utc().unix_timestamp(), t) {
              error!("metrics database error: {:?}", e);
          }
          if let Some(bid) = backend_id {
-             if let Err(e) = self.store_time_metric(key, cluster_id, backend_id, t) {
+             if let Err(e) = self.store_time_metric(key, cluster_id, backend_id, unit, tags, t) {
+                 error!("metrics database error: {:?}", e);
+             }
+             let backend_prefix = format!("{}\t{}\t{}", key, cluster_id, bid);
+             if let Err(e) = self.store_time_histogram(&backend_prefix, true, OffsetDateTime::now_utc().unix_timestamp(), t) {
                  error!("metrics database error: {:?}", e);
              }
          }
       } else {
-          if let Err(e) = self.store_metric(&format!("{}\t{}", key, cluster_id), cluster_id, None, &metric) {
+          if let Err(e) = self.store_metric(&format!("{}\t{}", key, cluster_id), cluster_id, None, unit, tags, &metric) {
               error!("metrics database error: {:?}", e);
           }
           if let Some(bid) = backend_id {
-              if let Err(e) = self.store_metric(&format!("{}\t{}\t{}", key, cluster_id, bid), cluster_id, backend_id, &metric) {
+              if let Err(e) = self.store_metric(&format!("{}\t{}\t{}", key, cluster_id, bid), cluster_id, backend_id, unit, tags, &metric) {
                   error!("metrics database error: {:?}", e);
               }
           }
       }
   }
 
-  fn store_metric(&mut self, key_prefix: &str, id: &str, backend_id: Option<&str>, metric: &MetricData) -> Result<(), sled::Error> {
+  fn store_metric(&mut self, key_prefix: &str, id: &str, backend_id: Option<&str>, unit: Option<MetricUnit>, tags: &[(String, String)], metric: &MetricData) -> Result<(), sled::Error> {
       info!("metric: {} {} {:?} {:?}", key_prefix, id, backend_id, metric);
 
       if !self.metrics.contains_key(key_prefix) {
@@ -522,7 +1362,9 @@ impl LocalDrain {
               MetricMeta::Cluster
           };
 
-          self.metrics.insert(key_prefix.to_string(), (meta, kind));
+          let unit = unit.unwrap_or_else(|| infer_unit(key_prefix, &kind));
+          let description = metric_description(key_prefix, &kind, unit);
+          self.metrics.insert(key_prefix.to_string(), (meta, kind, unit, description, tags.to_vec()));
           let end = format!("{}\x7F", key_prefix);
           if backend_id.is_some() {
               self.backend_tree.insert(end.as_bytes(), &0u64.to_le_bytes())?;
@@ -531,6 +1373,17 @@ impl LocalDrain {
           }
       }
 
+      // keep the registry tags in sync with the latest caller-supplied set: they
+      // are key-constant dimensions in practice, but refreshing here means a
+      // non-empty set supplied after first registration is not dropped
+      if !tags.is_empty() {
+          if let Some(entry) = self.metrics.get_mut(key_prefix) {
+              if entry.4.as_slice() != tags {
+                  entry.4 = tags.to_vec();
+              }
+          }
+      }
+
       match metric {
           MetricData::Gauge(i) => {
               self.store_gauge(&key_prefix, *i, backend_id.is_some())?;
@@ -542,7 +1395,8 @@ impl LocalDrain {
               self.store_count(&key_prefix, *i, backend_id.is_some())?;
           },
           MetricData::Time(i) => {
-              //self.store_time(&key_prefix, *i, backend_id.is_some())?;
+              let now = OffsetDateTime::now_utc();
+              self.store_time_histogram(key_prefix, backend_id.is_some(), now.unix_timestamp(), *i)?;
           },
       }
 
@@ -642,121 +1496,225 @@ impl LocalDrain {
   }
 
   fn aggregate_gauge(&mut self, key: &str, now: OffsetDateTime, is_backend: bool) -> Result<(), sled::Error> {
-      let timestamp = now.unix_timestamp();
-      let one_hour_ago = format!("{}\t{}", key, timestamp - 3600);
-      let one_minute_ago = format!("{}\t{}", key, timestamp - 60);
-      let now_key = format!("{}\t{}", key, timestamp);
+      // gauges downsample by keeping the last value seen in the closing window
+      self.aggregate_tiered(key, now, is_backend, Downsample::Last)
+  }
 
-      let tree = if is_backend {
-          &mut self.backend_tree
-      } else {
-          &mut self.cluster_tree
-      };
+  fn aggregate_count(&mut self, key: &str, now: OffsetDateTime, is_backend: bool) -> Result<(), sled::Error> {
+      // counters downsample by summing the closing window
+      self.aggregate_tiered(key, now, is_backend, Downsample::Sum)
+  }
 
-      // aggregate 60 measures in a point at the last minute
-      let mut value = None;
-      for res in tree.range(one_minute_ago.as_bytes()..now_key.as_bytes()) {
-          let (k, v) = res?;
-          value = Some(usize::from_le_bytes((*v).try_into().unwrap()));
-          info!("removing {} -> {:?}", unsafe { std::str::from_utf8_unchecked(&k) }, u64::from_le_bytes((*v).try_into().unwrap()));
-          tree.remove(k)?;
+  // roll points up through the configured retention tiers, finest first. When
+  // the wall clock crosses a tier's resolution boundary, every point from the
+  // next-finer tier in the closing window is folded into a single point at that
+  // tier's bucket; anything older than the coarsest tier's retention is pruned.
+  // This replaces the old hardcoded 60s/1h/24h cadence with the policy in
+  // `self.retention`.
+  fn aggregate_tiered(&mut self, key: &str, now: OffsetDateTime, is_backend: bool, downsample: Downsample) -> Result<(), sled::Error> {
+      let policy = self.retention.clone();
+      let use_compression = self.use_compression;
+      let timestamp = now.unix_timestamp();
+      let tiers = &policy.tiers;
+      if tiers.len() < 2 {
+          return Ok(());
       }
 
-      if let Some(v) = value {
-          info!("reinserting {} -> {:?}", one_minute_ago, v);
-          tree.insert(one_minute_ago.as_bytes(), &v.to_le_bytes())?;
-      }
+      // the coarsest closing window, archived as a compressed block when
+      // compression is enabled instead of folded into one lossy point
+      let mut sealed: Vec<(i64, i64)> = Vec::new();
+      let coarsest = &tiers[tiers.len() - 1];
 
-      // aggregate 60 measures in a point at the last hour
-      if now.minute() == 0 {
-          let mut value = None;
-          for res in tree.range(one_hour_ago.as_bytes()..one_minute_ago.as_bytes()) {
-              let (k, v) = res?;
-              value = Some(usize::from_le_bytes((*v).try_into().unwrap()));
-              info!("removing {} -> {:?}", unsafe { std::str::from_utf8_unchecked(&k) }, u64::from_le_bytes((*v).try_into().unwrap()));
-              tree.remove(k)?;
-          }
+      {
+          let tree = if is_backend {
+              &mut self.backend_tree
+          } else {
+              &mut self.cluster_tree
+          };
+
+          for w in 1..tiers.len() {
+              let coarse_res = tiers[w].resolution;
+              // only fold when the clock is aligned to this tier's resolution
+              if coarse_res <= 0 || timestamp % coarse_res != 0 {
+                  continue;
+              }
 
-          if let Some(v) = value {
-              info!("reinserting {} -> {:?}", one_hour_ago, v);
-              tree.insert(one_minute_ago.as_bytes(), &v.to_le_bytes())?;
+              // the window of finer points that just closed; the still-open
+              // finer bucket above it (if any) is left untouched
+              let window_start = format!("{}\t{}", key, timestamp - coarse_res);
+              let window_end = format!("{}\t{}", key, timestamp);
+              let bucket_key = format!("{}\t{}", key, timestamp - coarse_res);
+
+              let mut acc: Option<i64> = None;
+              let mut points: Vec<(i64, i64)> = Vec::new();
+              for res in tree.range(window_start.as_bytes()..window_end.as_bytes()) {
+                  let (k, v) = res?;
+                  let value = i64::from_le_bytes((*v).try_into().unwrap());
+                  if let Some(ts) = parse_point_timestamp(&k) {
+                      points.push((ts, value));
+                  }
+                  acc = Some(match (downsample, acc) {
+                      (Downsample::Sum, Some(a)) => a + value,
+                      (Downsample::Sum, None) | (Downsample::Last, _) => value,
+                  });
+                  info!("removing {} -> {:?}", unsafe { std::str::from_utf8_unchecked(&k) }, value);
+                  tree.remove(k)?;
+              }
+
+              if use_compression && w == tiers.len() - 1 {
+                  sealed = points;
+              } else if let Some(v) = acc {
+                  info!("reinserting {} -> {:?}", bucket_key, v);
+                  tree.insert(bucket_key.as_bytes(), &v.to_le_bytes())?;
+              }
           }
 
-          // remove all measures older than 24h
-          let one_day_ago = format!("{}\t{}", key, timestamp - 3600 * 24);
-          for res in tree.range(key.as_bytes()..one_day_ago.as_bytes()) {
-              let (k, v) = res?;
-              value = Some(usize::from_le_bytes((*v).try_into().unwrap()));
-              info!("removing {} -> {:?} (more than 24h)", unsafe { std::str::from_utf8_unchecked(&k) }, value);
+          // prune everything past the coarsest tier's retention
+          let horizon = format!("{}\t{}", key, timestamp - coarsest.retention);
+          let base = format!("{}\t", key);
+          for res in tree.range(base.as_bytes()..horizon.as_bytes()) {
+              let (k, _v) = res?;
+              info!("removing {} (past retention)", unsafe { std::str::from_utf8_unchecked(&k) });
               tree.remove(k)?;
           }
       }
 
+      if use_compression && !sealed.is_empty() {
+          self.store_block(key, is_backend, timestamp, &sealed)?;
+      }
+
       Ok(())
   }
 
-  fn aggregate_count(&mut self, key: &str, now: OffsetDateTime, is_backend: bool) -> Result<(), sled::Error> {
+  // roll time metrics up the same retention ladder as the scalar metrics. A
+  // time metric keeps four columns per window — the mergeable `.hist`
+  // histogram plus the `.count`/`.mean`/`.var` summary — and without this the
+  // per-second keys were never folded or pruned, so `merged_time_histogram`
+  // re-scanned and re-added every second of history on every query. Here each
+  // closing window's histograms are merged into one coarser `.hist` bucket (its
+  // count/mean/var recomputed from the merge), and everything past the coarsest
+  // tier's retention is dropped. When compression is on the closing coarsest
+  // window's mean series is archived as a float block instead of collapsed.
+  fn aggregate_time(&mut self, key: &str, now: OffsetDateTime, is_backend: bool) -> Result<(), sled::Error> {
+      let policy = self.retention.clone();
+      let use_compression = self.use_compression;
       let timestamp = now.unix_timestamp();
-      let one_hour_ago = format!("{}\t{}", key, timestamp - 3600);
-      let one_minute_ago = format!("{}\t{}", key, timestamp - 60);
-      let now_key = format!("{}\t{}", key, timestamp);
+      let tiers = &policy.tiers;
+      if tiers.len() < 2 {
+          return Ok(());
+      }
+      let coarsest = &tiers[tiers.len() - 1];
 
-      let tree = if is_backend {
-          &mut self.backend_tree
-      } else {
-          &mut self.cluster_tree
-      };
+      let hist_col  = format!("{}.hist ",  key);
+      let count_col = format!("{}.count ", key);
+      let mean_col  = format!("{}.mean ",  key);
+      let var_col   = format!("{}.var ",   key);
 
-      // aggregate 60 measures in a point at the last hour
-      let mut value = 0i64;
-      let mut found = false;
-      for res in tree.range(one_minute_ago.as_bytes()..now_key.as_bytes()) {
-          found = true;
-          let (k, v) = res?;
-          value += i64::from_le_bytes((*v).try_into().unwrap());
-          info!("removing {} -> {:?}", unsafe { std::str::from_utf8_unchecked(&k) }, u64::from_le_bytes((*v).try_into().unwrap()));
-          tree.remove(k)?;
-      }
+      // the coarsest closing window's per-window means, archived as a float
+      // block when compression is enabled instead of collapsed into one point
+      let mut sealed_mean: Vec<(i64, f64)> = Vec::new();
 
-      if found {
-          info!("reinserting {} -> {:?}", one_minute_ago, value);
-          tree.insert(one_minute_ago.as_bytes(), &value.to_le_bytes())?;
-      }
+      {
+          let tree = if is_backend {
+              &mut self.backend_tree
+          } else {
+              &mut self.cluster_tree
+          };
 
-      // remove all measures older than 24h
-      if now.minute() == 0 {
-          let mut value = 0i64;
-          let mut found = false;
-          for res in tree.range(one_hour_ago.as_bytes()..one_minute_ago.as_bytes()) {
-              found = true;
-              let (k, v) = res?;
-              value += i64::from_le_bytes((*v).try_into().unwrap());
-              info!("removing {} -> {:?}", unsafe { std::str::from_utf8_unchecked(&k) }, u64::from_le_bytes((*v).try_into().unwrap()));
-              tree.remove(k)?;
-          }
+          for w in 1..tiers.len() {
+              let coarse_res = tiers[w].resolution;
+              if coarse_res <= 0 || timestamp % coarse_res != 0 {
+                  continue;
+              }
+              let is_coarsest = w == tiers.len() - 1;
+
+              // the closing window, including the finer bucket that just closed
+              let win_start = timestamp - coarse_res;
+              let win_end = timestamp;
+
+              // merge the histogram windows that just closed into one
+              let mut merged: Option<Histogram<u32>> = None;
+              let hs = format!("{}\t{}", hist_col, win_start);
+              let he = format!("{}\t{}", hist_col, win_end);
+              for res in tree.range(hs.as_bytes()..he.as_bytes()) {
+                  let (k, v) = res?;
+                  if let Some(h) = deserialize_histogram(&v) {
+                      // seed from a fresh auto-resizing histogram so a window whose
+                      // max exceeds the accumulator's bound can't be dropped by `add`
+                      let a = merged.get_or_insert_with(|| {
+                          let mut acc = Histogram::new(TIME_METRIC_SIGFIG).unwrap();
+                          acc.auto_resize(true);
+                          acc
+                      });
+                      if let Err(e) = a.add(&h) {
+                          error!("could not merge time histogram window: {:?}", e);
+                      }
+                  }
+                  tree.remove(k)?;
+              }
 
-          if found {
-              info!("reinserting {} -> {:?}", one_hour_ago, value);
-              tree.insert(one_hour_ago.as_bytes(), &value.to_le_bytes())?;
+              // collect and drop the finer mean points in the window
+              let ms = format!("{}\t{}", mean_col, win_start);
+              let me_ = format!("{}\t{}", mean_col, win_end);
+              for res in tree.range(ms.as_bytes()..me_.as_bytes()) {
+                  let (k, v) = res?;
+                  if is_coarsest {
+                      if let Some(ts) = parse_point_timestamp(&k) {
+                          sealed_mean.push((ts, f64::from_le_bytes((*v).try_into().unwrap())));
+                      }
+                  }
+                  tree.remove(k)?;
+              }
+
+              // drop the finer count/var points; they are recomputed below
+              for col in [&count_col, &var_col] {
+                  let cs = format!("{}\t{}", col, win_start);
+                  let ce = format!("{}\t{}", col, win_end);
+                  for res in tree.range(cs.as_bytes()..ce.as_bytes()) {
+                      let (k, _v) = res?;
+                      tree.remove(k)?;
+                  }
+              }
+
+              if let Some(h) = &merged {
+                  let bucket = win_start;
+                  if let Some(buf) = serialize_histogram(h) {
+                      tree.insert(format!("{}\t{}", hist_col, bucket).as_bytes(), buf)?;
+                  }
+                  tree.insert(format!("{}\t{}", count_col, bucket).as_bytes(), &(h.len() as i64).to_le_bytes())?;
+                  let var = h.stdev() * h.stdev();
+                  tree.insert(format!("{}\t{}", var_col, bucket).as_bytes(), &var.to_le_bytes())?;
+                  // keep a collapsed mean point unless this window is being
+                  // archived into a float block below
+                  if !(use_compression && is_coarsest) {
+                      tree.insert(format!("{}\t{}", mean_col, bucket).as_bytes(), &h.mean().to_le_bytes())?;
+                  }
+              }
           }
 
-          // remove all measures older than 24h
-          let one_day_ago = format!("{}\t{}", key, timestamp - 3600 * 24);
-          for res in tree.range(key.as_bytes()..one_day_ago.as_bytes()) {
-              let (k, v) = res?;
-              value = i64::from_le_bytes((*v).try_into().unwrap());
-              info!("removing {} -> {:?} (more than 24h)", unsafe { std::str::from_utf8_unchecked(&k) }, value);
-              tree.remove(k)?;
+          // prune every column past the coarsest tier's retention
+          let horizon = timestamp - coarsest.retention;
+          for col in [&hist_col, &count_col, &mean_col, &var_col] {
+              let start = format!("{}\t", col);
+              let end = format!("{}\t{}", col, horizon);
+              for res in tree.range(start.as_bytes()..end.as_bytes()) {
+                  let (k, _v) = res?;
+                  tree.remove(k)?;
+              }
           }
       }
 
+      if use_compression && !sealed_mean.is_empty() {
+          self.store_float_block(&mean_col, is_backend, timestamp, &sealed_mean)?;
+      }
+
       Ok(())
   }
 
-  fn store_time_metric(&mut self, key: &str, cluster_id: &str, backend_id: Option<&str>, t: usize) -> Result<(), sled::Error> {
+  fn store_time_metric(&mut self, key: &str, cluster_id: &str, backend_id: Option<&str>, unit: Option<MetricUnit>, tags: &[(String, String)], t: usize) -> Result<(), sled::Error> {
       let now = OffsetDateTime::now_utc();
       let timestamp = now.unix_timestamp();
-      let _res = self.store_time_metric_at(key, cluster_id, backend_id, timestamp, t)?;
+      let _res = self.store_time_metric_at(key, cluster_id, backend_id, unit, tags, timestamp, t)?;
 
       let second = now.second();
       // we also aggregate at second zero
@@ -764,17 +1722,23 @@ impl LocalDrain {
           let previous_minute = now - time::Duration::seconds(second as i64);
           let timestamp = previous_minute.unix_timestamp();
           info!("WILL REWRITE TIME METRIC AT {}", timestamp);
-          let _res = self.store_time_metric_at(key, cluster_id, backend_id, timestamp, t)?;
-          //self.aggregate_count(key, now, is_backend)?;
+          let _res = self.store_time_metric_at(key, cluster_id, backend_id, unit, tags, timestamp, t)?;
       } else {
-          //FIXME: here we should delete all the measurements for the previous 60 seconds
+          // the minute just closed: roll the per-second windows up the tiers
+          // and prune anything past the coarsest retention
+          let prefix = if let Some(bid) = backend_id {
+              format!("{}\t{}\t{}", key, cluster_id, bid)
+          } else {
+              format!("{}\t{}", key, cluster_id)
+          };
+          self.aggregate_time(&prefix, now, backend_id.is_some())?;
       }
 
       Ok(())
     }
 
   fn store_time_metric_at(&mut self, key: &str, cluster_id: &str,
-                          backend_id: Option<&str>, timestamp: i64, t: usize) -> Result<(), sled::Error> {
+                          backend_id: Option<&str>, unit: Option<MetricUnit>, tags: &[(String, String)], timestamp: i64, t: usize) -> Result<(), sled::Error> {
       let key_prefix = if let Some(bid) = backend_id {
           format!("{}\t{}\t{}", key, cluster_id, bid)
       } else {
@@ -784,13 +1748,6 @@ impl LocalDrain {
       let count_key_prefix = format!("{}.count ", key_prefix);
       let mean_key_prefix = format!("{}.mean ", key_prefix);
       let var_key_prefix = format!("{}.var ", key_prefix);
-      let p50_key_prefix = format!("{}.p50 ", key_prefix);
-      let p90_key_prefix = format!("{}.p90 ", key_prefix);
-      let p99_key_prefix = format!("{}.p99 ", key_prefix);
-      let p99_9_key_prefix = format!("{}.p99.9 ", key_prefix);
-      let p99_99_key_prefix = format!("{}.p99.99 ", key_prefix);
-      let p99_999_key_prefix = format!("{}.p99.999 ", key_prefix);
-      let p100_key_prefix = format!("{}.p100 ", key_prefix);
 
       if !self.metrics.contains_key(&key_prefix) {
           let meta = if backend_id.is_some() {
@@ -799,40 +1756,29 @@ impl LocalDrain {
               MetricMeta::Cluster
           };
 
-          self.metrics.insert(key_prefix.to_string(), (meta, MetricKind::Time));
+          let unit = unit.unwrap_or(MetricUnit::Milliseconds);
+          let description = metric_description(&key_prefix, &MetricKind::Time, unit);
+          self.metrics.insert(key_prefix.to_string(), (meta, MetricKind::Time, unit, description, tags.to_vec()));
 
           let count_end = format!("{}\x7F", count_key_prefix);
           let mean_end = format!("{}\x7F", mean_key_prefix);
           let var_end = format!("{}\x7F", var_key_prefix);
-          let p50_end = format!("{}\x7F", p50_key_prefix);
-          let p90_end = format!("{}\x7F", p90_key_prefix);
-          let p99_end = format!("{}\x7F", p99_key_prefix);
-          let p99_9_end = format!("{}\x7F", p99_9_key_prefix);
-          let p99_99_end = format!("{}\x7F", p99_99_key_prefix);
-          let p99_999_end = format!("{}\x7F", p99_999_key_prefix);
-          let p100_end = format!("{}\x7F", p100_key_prefix);
-          if backend_id.is_some() {
-              self.backend_tree.insert(count_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(mean_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(var_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p50_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p90_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p99_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p99_9_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p99_99_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p99_999_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.backend_tree.insert(p100_end.as_bytes(), &0u64.to_le_bytes())?;
+          let tree = if backend_id.is_some() {
+              &mut self.backend_tree
           } else {
-              self.cluster_tree.insert(count_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(mean_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(var_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p50_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p90_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p99_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p99_9_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p99_99_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p99_999_end.as_bytes(), &0u64.to_le_bytes())?;
-              self.cluster_tree.insert(p100_end.as_bytes(), &0u64.to_le_bytes())?;
+              &mut self.cluster_tree
+          };
+          tree.insert(count_end.as_bytes(), &0u64.to_le_bytes())?;
+          tree.insert(mean_end.as_bytes(), &0u64.to_le_bytes())?;
+          tree.insert(var_end.as_bytes(), &0u64.to_le_bytes())?;
+      }
+
+      // refresh the registry tags with the latest caller set (see `store_metric`)
+      if !tags.is_empty() {
+          if let Some(entry) = self.metrics.get_mut(&key_prefix) {
+              if entry.4.as_slice() != tags {
+                  entry.4 = tags.to_vec();
+              }
           }
       }
 
@@ -845,119 +1791,30 @@ impl LocalDrain {
       let count_key = format!("{}\t{}", count_key_prefix, timestamp);
       let mean_key = format!("{}\t{}", mean_key_prefix, timestamp);
       let var_key = format!("{}\t{}", var_key_prefix, timestamp);
-      let p50_key = format!("{}\t{}", p50_key_prefix, timestamp);
-      let p90_key = format!("{}\t{}", p90_key_prefix, timestamp);
-      let p99_key = format!("{}\t{}", p99_key_prefix, timestamp);
-      let p99_9_key = format!("{}\t{}", p99_9_key_prefix, timestamp);
-      let p99_99_key = format!("{}\t{}", p99_99_key_prefix, timestamp);
-      let p99_999_key = format!("{}\t{}", p99_999_key_prefix, timestamp);
-      let p100_key = format!("{}\t{}", p100_key_prefix, timestamp);
 
+      // the mergeable per-window histogram is persisted separately via
+      // `store_time_histogram`; here we only keep the count/mean/var summary
       match tree.get(count_key.as_bytes())? {
           None => {
               tree.insert(count_key.as_bytes(), &1usize.to_le_bytes())?;
               tree.insert(mean_key.as_bytes(), &(t as f64).to_le_bytes())?;
               tree.insert(var_key.as_bytes(), &0f64.to_le_bytes())?;
-              tree.insert(p50_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p90_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p99_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p99_9_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p99_99_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p99_999_key.as_bytes(), &t.to_le_bytes())?;
-              tree.insert(p100_key.as_bytes(), &t.to_le_bytes())?;
-              info!("TIME stored new {}: {}", p50_key, t);
+              info!("TIME stored new {}: {}", count_key, t);
           },
           Some(v) => {
               let old_count = i64::from_le_bytes((*v).try_into().unwrap());
               tree.insert(count_key.as_bytes(), &(old_count+1).to_le_bytes())?;
 
-              match tree.get(mean_key.as_bytes())? {
-                  None => {
-                      tree.insert(mean_key.as_bytes(), &t.to_le_bytes())?;
-                  },
-                  Some(mean_v) => {
-                      let old_mean = f64::from_le_bytes((*mean_v).try_into().unwrap());
-                      let new_mean = (old_mean * old_count as f64 + t as f64) / (old_count as f64 + 1f64);
-
-                      tree.insert(mean_key.as_bytes(), &new_mean.to_le_bytes())?;
+              if let Some(mean_v) = tree.get(mean_key.as_bytes())? {
+                  let old_mean = f64::from_le_bytes((*mean_v).try_into().unwrap());
+                  let new_mean = (old_mean * old_count as f64 + t as f64) / (old_count as f64 + 1f64);
+                  tree.insert(mean_key.as_bytes(), &new_mean.to_le_bytes())?;
 
-                      match tree.get(var_key.as_bytes())? {
-                          None => {
-                              tree.insert(var_key.as_bytes(), &0f64.to_le_bytes())?;
-                          },
-                          Some(var_v) => {
-                              let old_var = f64::from_le_bytes((*var_v).try_into().unwrap());
-                              let deviation = t as f64 - old_mean;
-                              let new_var = (old_var * old_count as f64 + deviation * deviation) / (old_count as f64 +1f64);
-                              tree.insert(var_key.as_bytes(), &new_var.to_le_bytes())?;
-
-                              let standard_dev = new_var.sqrt();
-
-                              if let Some(old_v) = tree.get(p50_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.50f64);
-                                  tree.insert(p50_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p50_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p90_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.90f64);
-                                  tree.insert(p90_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p90_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p99_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.99f64);
-                                  tree.insert(p99_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p99_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p99_9_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.999f64);
-                                  tree.insert(p99_9_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p99_9_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p99_99_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.9999f64);
-                                  tree.insert(p99_99_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p99_99_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p99_999_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  let new_percentile = calculate_percentile(old_percentile, t,
-                                                                            standard_dev, 0.99999f64);
-                                  tree.insert(p99_999_key.as_bytes(), &new_percentile.to_le_bytes())?;
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p99_999_key, new_percentile,
-                                    old_percentile, t);
-                              }
-
-                              if let Some(old_v) = tree.get(p100_key.as_bytes())? {
-                                  let old_percentile = usize::from_le_bytes((*old_v).try_into().unwrap());
-                                  // the 100 percentile is the largest value
-                                  if t > old_percentile {
-                                      tree.insert(p100_key.as_bytes(), &t.to_le_bytes())?;
-                                  }
-                                  info!("TIME rewrote {}: {} (old={}, t={})", p100_key, t,
-                                    old_percentile, t);
-                              }
-                          }
-                      }
+                  if let Some(var_v) = tree.get(var_key.as_bytes())? {
+                      let old_var = f64::from_le_bytes((*var_v).try_into().unwrap());
+                      let deviation = t as f64 - old_mean;
+                      let new_var = (old_var * old_count as f64 + deviation * deviation) / (old_count as f64 + 1f64);
+                      tree.insert(var_key.as_bytes(), &new_var.to_le_bytes())?;
                   }
               }
           }
@@ -966,13 +1823,150 @@ impl LocalDrain {
       Ok(())
   }
 
+  // persist a latency sample into the mergeable histogram for its aggregation
+  // window. One `Histogram<u32>` is serialized per `{key_prefix}.hist \t{timestamp}`
+  // key, so windows can later be merged exactly rather than estimated.
+  fn store_time_histogram(&mut self, key_prefix: &str, is_backend: bool, timestamp: i64, t: usize) -> Result<(), sled::Error> {
+      let hist_key = format!("{}.hist \t{}", key_prefix, timestamp);
+
+      let tree = if is_backend {
+          &mut self.backend_tree
+      } else {
+          &mut self.cluster_tree
+      };
+
+      let mut hist = match tree.get(hist_key.as_bytes())? {
+          Some(v) => deserialize_histogram(&v).unwrap_or_else(|| {
+              Histogram::new(TIME_METRIC_SIGFIG).unwrap()
+          }),
+          None => Histogram::new(TIME_METRIC_SIGFIG).unwrap(),
+      };
+
+      if let Err(e) = hist.record(t as u64) {
+          error!("could not record time metric {} in histogram: {:?}", t, e);
+      }
+
+      if let Some(buf) = serialize_histogram(&hist) {
+          tree.insert(hist_key.as_bytes(), buf)?;
+      }
+
+      Ok(())
+  }
+
+  // merge every stored histogram window for a metric key into a single
+  // accumulator, skipping (and logging) any buffer that fails to deserialize
+  fn merged_time_histogram(&self, key_prefix: &str, is_backend: bool) -> Result<Option<Histogram<u32>>, sled::Error> {
+      let tree = if is_backend {
+          &self.backend_tree
+      } else {
+          &self.cluster_tree
+      };
+
+      let start = format!("{}.hist ", key_prefix);
+      let end = format!("{}.hist \x7F", key_prefix);
+
+      let mut acc: Option<Histogram<u32>> = None;
+      for res in tree.range(start.as_bytes()..end.as_bytes()) {
+          let (_k, v) = res?;
+          if let Some(hist) = deserialize_histogram(&v) {
+              // seed from a fresh auto-resizing histogram so a later window with a
+              // higher max can never be rejected (and silently dropped) by `add`
+              let a = acc.get_or_insert_with(|| {
+                  let mut h = Histogram::new(TIME_METRIC_SIGFIG).unwrap();
+                  h.auto_resize(true);
+                  h
+              });
+              if let Err(e) = a.add(&hist) {
+                  error!("could not merge time histogram window: {:?}", e);
+              }
+          }
+      }
+
+      Ok(acc)
+  }
+
+  // pack a sealed run of integer points into one Gorilla-compressed value under
+  // the run's closing timestamp, kept apart from the live per-point keys
+  fn store_block(&mut self, key: &str, is_backend: bool, timestamp: i64, points: &[(i64, i64)]) -> Result<(), sled::Error> {
+      let block_key = format!("{}\t{}", key, timestamp);
+      let blob = compress_int_block(points);
+      let blocks = if is_backend {
+          &mut self.backend_blocks
+      } else {
+          &mut self.cluster_blocks
+      };
+      blocks.insert(block_key.as_bytes(), blob)?;
+      Ok(())
+  }
+
+  // decode every compressed block for a metric and return the points that fall
+  // inside `[from, to]`; used by the read side to reconstruct archived history
+  pub fn blocks_in_range(&self, key: &str, is_backend: bool, from: i64, to: i64) -> Result<Vec<(i64, i64)>, sled::Error> {
+      let blocks = if is_backend {
+          &self.backend_blocks
+      } else {
+          &self.cluster_blocks
+      };
+
+      let start = format!("{}\t", key);
+      let end = format!("{}\x7F", key);
+
+      let mut out = Vec::new();
+      for res in blocks.range(start.as_bytes()..end.as_bytes()) {
+          let (_k, v) = res?;
+          for (ts, value) in decompress_int_block(&v) {
+              if ts >= from && ts <= to {
+                  out.push((ts, value));
+              }
+          }
+      }
+      Ok(out)
+  }
+
+  // pack a sealed run of float points (e.g. an archived window-mean series) into
+  // one XOR-compressed block under the run's closing timestamp
+  fn store_float_block(&mut self, col_key: &str, is_backend: bool, timestamp: i64, points: &[(i64, f64)]) -> Result<(), sled::Error> {
+      let block_key = format!("{}\t{}", col_key, timestamp);
+      let blob = compress_float_block(points);
+      let blocks = if is_backend {
+          &mut self.backend_blocks
+      } else {
+          &mut self.cluster_blocks
+      };
+      blocks.insert(block_key.as_bytes(), blob)?;
+      Ok(())
+  }
+
+  // decode every float block for a column and return the points inside `[from, to]`
+  fn float_blocks_in_range(&self, col_key: &str, is_backend: bool, from: i64, to: i64) -> Result<Vec<(i64, f64)>, sled::Error> {
+      let blocks = if is_backend {
+          &self.backend_blocks
+      } else {
+          &self.cluster_blocks
+      };
+
+      let start = format!("{}\t", col_key);
+      let end = format!("{}\x7F", col_key);
+
+      let mut out = Vec::new();
+      for res in blocks.range(start.as_bytes()..end.as_bytes()) {
+          let (_k, v) = res?;
+          for (ts, value) in decompress_float_block(&v) {
+              if ts >= from && ts <= to {
+                  out.push((ts, value));
+              }
+          }
+      }
+      Ok(out)
+  }
+
   pub fn clear(&mut self, now: OffsetDateTime) -> Result<(), sled::Error> {
       info!("will clear old data from the metrics database");
       //self.db.clear();
       //
 
       let metrics = self.metrics.clone();
-      for (key, (meta, kind)) in metrics.iter() {
+      for (key, (meta, kind, _unit, _description, _tags)) in metrics.iter() {
           info!("will aggregate metrics for key '{}'", key);
 
           let is_backend = *meta == MetricMeta::ClusterBackend;
@@ -984,6 +1978,7 @@ impl LocalDrain {
                   self.aggregate_count(key, now, is_backend)?;
               },
               MetricKind::Time => {
+                  self.aggregate_time(key, now, is_backend)?;
               }
           }
 
@@ -1023,13 +2018,124 @@ impl LocalDrain {
       Ok(())
   }
 
+  // Start timing a section of code. The returned guard records the elapsed
+  // duration as a time metric when it is dropped, so a call site can simply
+  // write `let _m = drain.measure(key, cluster_id, backend_id);` at the top
+  // of the scope it wants to measure.
+  pub fn measure<'a>(&'a mut self, key: &str, cluster_id: &str, backend_id: Option<&str>) -> Measure<'a> {
+      Measure {
+          drain: self,
+          key: key.to_string(),
+          cluster_id: cluster_id.to_string(),
+          backend_id: backend_id.map(|b| b.to_string()),
+          stopwatch: Stopwatch::start(),
+      }
+  }
+
+}
+
+
+// A cheap monotonic stopwatch. On x86_64 it reads the timestamp counter
+// through `rdtscp` and converts ticks to nanoseconds with a factor calibrated
+// once against the wall clock; everywhere else it falls back to `Instant`.
+#[cfg(target_arch = "x86_64")]
+mod monoclock {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    #[inline]
+    pub fn read() -> u64 {
+        let mut aux = 0u32;
+        // SAFETY: rdtscp is available on every x86_64 CPU and only reads the
+        // timestamp counter, leaving no observable side effects.
+        unsafe { core::arch::x86_64::__rdtscp(&mut aux) }
+    }
+
+    // Nanoseconds per TSC tick, measured once on first use.
+    pub fn ns_per_tick() -> f64 {
+        static NS_PER_TICK: OnceLock<f64> = OnceLock::new();
+        *NS_PER_TICK.get_or_init(|| {
+            let start_tsc = read();
+            let start = Instant::now();
+            while start.elapsed().as_micros() < 2_000 {}
+            let elapsed_ns = start.elapsed().as_nanos() as f64;
+            let elapsed_ticks = read().saturating_sub(start_tsc) as f64;
+            if elapsed_ticks > 0.0 {
+                elapsed_ns / elapsed_ticks
+            } else {
+                1.0
+            }
+        })
+    }
 }
 
+#[cfg(target_arch = "x86_64")]
+struct Stopwatch {
+    start: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Stopwatch {
+    fn start() -> Stopwatch {
+        Stopwatch { start: monoclock::read() }
+    }
+
+    fn elapsed_nanos(&self) -> u64 {
+        let delta = monoclock::read().saturating_sub(self.start) as f64;
+        (delta * monoclock::ns_per_tick()) as u64
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+struct Stopwatch {
+    start: Instant,
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+impl Stopwatch {
+    fn start() -> Stopwatch {
+        Stopwatch { start: Instant::now() }
+    }
+
+    fn elapsed_nanos(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
+
+// RAII guard returned by `LocalDrain::measure`. It times the enclosing scope
+// and, on `Drop`, records the elapsed milliseconds into the time-metric store.
+pub struct Measure<'a> {
+    drain:      &'a mut LocalDrain,
+    key:        String,
+    cluster_id: String,
+    backend_id: Option<String>,
+    stopwatch:  Stopwatch,
+}
+
+impl<'a> Drop for Measure<'a> {
+    fn drop(&mut self) {
+        let elapsed_ms = (self.stopwatch.elapsed_nanos() / 1_000_000) as usize;
+        let backend_id = self.backend_id.as_deref();
+        if let Err(e) = self.drain.store_time_metric(&self.key, &self.cluster_id, backend_id, Some(MetricUnit::Milliseconds), &[], elapsed_ms) {
+            error!("could not record measured time metric {}: {:?}", self.key, e);
+        }
+        // percentiles are read from the mergeable histogram, so the sample
+        // goes into its window as well (as `receive_cluster_metric` does)
+        let prefix = match backend_id {
+            Some(bid) => format!("{}\t{}\t{}", self.key, self.cluster_id, bid),
+            None      => format!("{}\t{}", self.key, self.cluster_id),
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if let Err(e) = self.drain.store_time_histogram(&prefix, backend_id.is_some(), now, elapsed_ms) {
+            error!("could not record measured time metric {}: {:?}", self.key, e);
+        }
+    }
+}
 
 impl Subscriber for LocalDrain {
   fn receive_metric(&mut self, key: &'static str, cluster_id: Option<&str>, backend_id: Option<&str>, metric: MetricData) {
     if let Some(id) = cluster_id {
-      self.receive_cluster_metric(key, id, backend_id, metric);
+      self.receive_cluster_metric(key, id, backend_id, None, &[], metric);
     } else if !self.data.contains_key(key) {
       self.data.insert(
         String::from(key),
@@ -1043,19 +2149,75 @@ impl Subscriber for LocalDrain {
   }
 }
 
-// implementation of an algorithm from https://mjambon.com/2016-07-23-moving-percentile/
-fn calculate_percentile(old_value: usize, measure: usize, standard_deviation: f64, percentile: f64) -> usize {
-    // to be adated can be between 0.01 and 0.001
-    let r = 0.01f64;
-    let delta = standard_deviation * r;
-
-    if measure == old_value {
-        old_value
-    } else if measure < old_value {
-        let new_value = old_value as f64 - delta / percentile;
-        new_value as usize
-    } else {
-        let new_value = old_value as f64 + delta / ( 1f64 - percentile );
-        new_value as usize
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_block_round_trips() {
+        // empty and single-point runs are degenerate but must survive
+        assert!(decompress_int_block(&compress_int_block(&[])).is_empty());
+        assert_eq!(decompress_int_block(&compress_int_block(&[(100, 7)])), vec![(100, 7)]);
+
+        // a steady cadence with both rising and falling values exercises the
+        // delta-of-delta and zig-zag paths
+        let points: Vec<(i64, i64)> = vec![
+            (1_000, 5), (1_001, 8), (1_002, 8), (1_003, 3),
+            (1_004, 3), (1_005, 42), (1_010, -17), (1_020, 0),
+        ];
+        assert_eq!(decompress_int_block(&compress_int_block(&points)), points);
+    }
+
+    #[test]
+    fn float_block_round_trips() {
+        assert!(decompress_float_block(&compress_float_block(&[])).is_empty());
+
+        let points: Vec<(i64, f64)> = vec![
+            (1_000, 1.5), (1_001, 1.5), (1_002, 2.25), (1_003, 0.0),
+            (1_004, -3.75), (1_010, 1234.5), (1_020, 0.125),
+        ];
+        let decoded = decompress_float_block(&compress_float_block(&points));
+        assert_eq!(decoded.len(), points.len());
+        for ((ts, v), (dts, dv)) in points.iter().zip(decoded.iter()) {
+            assert_eq!(ts, dts);
+            // compare the bit patterns so the check is exact for 0.0/-0.0 too
+            assert_eq!(v.to_bits(), dv.to_bits());
+        }
+    }
+
+    #[test]
+    fn aggregate_tiered_rolls_up_and_prunes() {
+        let policy = RetentionPolicy::new(vec![
+            RetentionTier { resolution: 1,  retention: 60 },
+            RetentionTier { resolution: 60, retention: 120 },
+        ]);
+        let mut drain = LocalDrain::new("test".to_string()).with_retention(policy);
+
+        let t: i64 = 3600; // aligned to the 60s tier boundary
+        let key = "requests\tcluster-1";
+
+        // sixty one-per-second counter points in the closing minute [t-60, t)
+        for ts in (t - 60)..t {
+            let k = format!("{}\t{}", key, ts);
+            drain.cluster_tree.insert(k.as_bytes(), &1i64.to_le_bytes()).unwrap();
+        }
+        // a point already past the coarsest tier's retention horizon
+        let old = format!("{}\t{}", key, t - 200);
+        drain.cluster_tree.insert(old.as_bytes(), &1i64.to_le_bytes()).unwrap();
+
+        let now = OffsetDateTime::from_unix_timestamp(t);
+        drain.aggregate_tiered(key, now, false, Downsample::Sum).unwrap();
+
+        // the minute folded into a single summed bucket at its start
+        let bucket = format!("{}\t{}", key, t - 60);
+        let v = drain.cluster_tree.get(bucket.as_bytes()).unwrap().unwrap();
+        assert_eq!(i64::from_le_bytes((*v).try_into().unwrap()), 60);
+
+        // the per-second points are gone
+        let sample = format!("{}\t{}", key, t - 30);
+        assert!(drain.cluster_tree.get(sample.as_bytes()).unwrap().is_none());
+
+        // the point past retention was pruned
+        assert!(drain.cluster_tree.get(old.as_bytes()).unwrap().is_none());
     }
 }